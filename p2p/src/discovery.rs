@@ -0,0 +1,321 @@
+//! Self-maintaining peer discovery on top of `PeerLink`.
+//! - Maintains an address book learned from `PeerMessage::Peers` exchanges.
+//! - Keeps the number of live links at a configurable target by dialing
+//!   unconnected entries from the book.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand_core::{CryptoRng, RngCore};
+use tokio::sync;
+use tokio::time;
+
+use crate::cybershake;
+use crate::peer::{PeerAddr, PeerID, PeerLink, PeerMessage, PeerNotification};
+
+/// How often we ask connected peers for a fresh `Peers` list.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Connection state of an address book entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LinkState {
+    /// Known, but no `PeerLink` is currently established.
+    Disconnected,
+    /// Currently linked.
+    Connected,
+}
+
+struct BookEntry {
+    addr: PeerAddr,
+    last_seen: Instant,
+    state: LinkState,
+}
+
+/// Drives the network towards a configurable target peer count by dialing
+/// addresses learned from peers over `PeerMessage::Peers`.
+pub struct Discovery {
+    target_peer_count: usize,
+    /// Always keyed by `canonical_key`: a peer's root once known, or its
+    /// (possibly session-scoped, blinded) id otherwise.
+    book: HashMap<PeerID, BookEntry>,
+    /// Maps every session id we've ever seen for a book entry to the key
+    /// it's filed under, so a lookup driven only by a live session's
+    /// `PeerID` (as `record_hello`/`mark_disconnected` get from
+    /// `PeerNotification`) still finds an entry that's been re-keyed onto
+    /// its root by `reconcile_roots`.
+    session_index: HashMap<PeerID, PeerID>,
+}
+
+impl Discovery {
+    /// Creates an empty address book aiming for `target_peer_count` live links.
+    pub fn new(target_peer_count: usize) -> Self {
+        Self {
+            target_peer_count,
+            book: HashMap::new(),
+            session_index: HashMap::new(),
+        }
+    }
+
+    /// The key the book dedupes a `PeerAddr` under: its verified root
+    /// identity once known, falling back to the (possibly session-scoped,
+    /// blinded) id otherwise. This lets a peer that reconnects under a fresh
+    /// blinded session key collapse back onto its existing book entry once
+    /// that session reveals its root.
+    fn canonical_key(addr: &PeerAddr) -> PeerID {
+        addr.root.clone().unwrap_or_else(|| addr.id.clone())
+    }
+
+    /// Resolves a live session id to the book key it's filed under, via
+    /// `session_index`, falling back to the id itself for a session never
+    /// indexed (e.g. one not yet registered by `mark_connected`).
+    fn resolve_key(&self, session_id: &PeerID) -> PeerID {
+        self.session_index
+            .get(session_id)
+            .cloned()
+            .unwrap_or_else(|| session_id.clone())
+    }
+
+    /// Seeds the book with addresses known ahead of time (e.g. from config).
+    pub fn add_known(&mut self, addr: PeerAddr) {
+        let key = Self::canonical_key(&addr);
+        self.session_index.insert(addr.id.clone(), key.clone());
+        self.book.entry(key).or_insert_with(|| BookEntry {
+            addr,
+            last_seen: Instant::now(),
+            state: LinkState::Disconnected,
+        });
+    }
+
+    /// Merges a list of addresses received from a peer's `Peers` message,
+    /// deduping against the existing book. Returns the newly learned ones.
+    fn merge(&mut self, addrs: Vec<PeerAddr>) -> Vec<PeerAddr> {
+        let mut discovered = Vec::new();
+        for addr in addrs {
+            let key = Self::canonical_key(&addr);
+            self.session_index.insert(addr.id.clone(), key.clone());
+            match self.book.get_mut(&key) {
+                Some(entry) => {
+                    entry.last_seen = Instant::now();
+                    if entry.addr.root.is_none() && addr.root.is_some() {
+                        entry.addr.root = addr.root.clone();
+                    }
+                }
+                None => {
+                    self.book.insert(
+                        key,
+                        BookEntry {
+                            addr: addr.clone(),
+                            last_seen: Instant::now(),
+                            state: LinkState::Disconnected,
+                        },
+                    );
+                    discovered.push(addr);
+                }
+            }
+        }
+        discovered
+    }
+
+    /// Records that a peer is now live under session identity `session_id`,
+    /// connected via the given socket address, with `known_root` set if the
+    /// caller already has a verified root for this peer (e.g. redialing a
+    /// book entry reconciled by an earlier session). Keys the book entry by
+    /// `known_root` when given so a blinded peer's book entry is updated in
+    /// place across reconnects instead of duplicated under its new session
+    /// id, and always refreshes `session_index` so later lookups by
+    /// `session_id` (e.g. `record_hello`, `mark_disconnected`) still find it.
+    pub fn mark_connected(
+        &mut self,
+        session_id: &PeerID,
+        addr: SocketAddr,
+        known_root: Option<PeerID>,
+    ) {
+        let key = known_root.clone().unwrap_or_else(|| session_id.clone());
+        self.session_index.insert(session_id.clone(), key.clone());
+        let entry = self.book.entry(key).or_insert_with(|| BookEntry {
+            addr: PeerAddr {
+                id: session_id.clone(),
+                addr,
+                root: known_root.clone(),
+            },
+            last_seen: Instant::now(),
+            state: LinkState::Disconnected,
+        });
+        entry.addr.id = session_id.clone();
+        entry.addr.addr = addr;
+        if entry.addr.root.is_none() {
+            entry.addr.root = known_root;
+        }
+        entry.state = LinkState::Connected;
+        entry.last_seen = Instant::now();
+    }
+
+    /// Records the listening port a peer advertised via `PeerMessage::Hello`,
+    /// so its book entry becomes dialable from a fresh connection later.
+    /// Requires an existing entry (added by `mark_connected`) to learn the IP
+    /// from; inbound connections must call `mark_connected` with the
+    /// observed remote IP (port `0`) before `Hello` arrives.
+    fn record_hello(&mut self, from: &PeerID, port: u16) {
+        let key = self.resolve_key(from);
+        if let Some(entry) = self.book.get_mut(&key) {
+            entry.addr.addr.set_port(port);
+            entry.last_seen = Instant::now();
+        }
+    }
+
+    /// Records that a peer dropped, leaving it eligible for re-dialing.
+    pub fn mark_disconnected(&mut self, id: &PeerID) {
+        let key = self.resolve_key(id);
+        if let Some(entry) = self.book.get_mut(&key) {
+            entry.state = LinkState::Disconnected;
+        }
+    }
+
+    /// Folds each live link's revealed root (see `PeerLink::revealed_root`)
+    /// into its book entry, re-keying the entry onto the root once known so
+    /// discovery dedupes on stable identity rather than the blinded session id.
+    fn reconcile_roots(&mut self, links: &HashMap<PeerID, PeerLink>) {
+        for (session_id, link) in links.iter() {
+            let root = match link.revealed_root() {
+                Some(root) => root,
+                None => continue,
+            };
+            let key = self.resolve_key(session_id);
+            self.session_index.insert(session_id.clone(), root.clone());
+            if key == root {
+                continue;
+            }
+            if let Some(mut entry) = self.book.remove(&key) {
+                if entry.addr.root.is_none() {
+                    entry.addr.root = Some(root.clone());
+                }
+                self.book.insert(root, entry);
+            } else if let Some(entry) = self.book.get_mut(&root) {
+                if entry.addr.root.is_none() {
+                    entry.addr.root = Some(root);
+                }
+            }
+        }
+    }
+
+    fn live_count(&self) -> usize {
+        self.book
+            .values()
+            .filter(|e| e.state == LinkState::Connected)
+            .count()
+    }
+
+    /// Picks up to `n` disconnected entries to dial, oldest-seen first.
+    fn pick_candidates(&self, n: usize) -> Vec<PeerAddr> {
+        let mut candidates: Vec<&BookEntry> = self
+            .book
+            .values()
+            .filter(|e| e.state == LinkState::Disconnected)
+            .collect();
+        candidates.sort_by_key(|e| e.last_seen);
+        candidates.into_iter().take(n).map(|e| e.addr.clone()).collect()
+    }
+
+    /// Runs the discovery loop: on every `REFRESH_INTERVAL` tick, dials book
+    /// entries if we're short of `target_peer_count` live links, then asks
+    /// connected peers to refresh our view via `PeerMessage::Peers`. A link
+    /// that drops is replaced at the next tick, not as soon as it drops.
+    ///
+    /// `notifications`/`notifications_tx` are the two ends of the same
+    /// channel: `notifications` receives `PeerNotification`s from every
+    /// spawned link (including links spawned elsewhere, e.g. inbound
+    /// connections), and `notifications_tx` is handed to links this loop
+    /// dials itself so their notifications flow into that same channel
+    /// instead of being dropped.
+    ///
+    /// `inbound` registers links accepted elsewhere (e.g. a listener task):
+    /// the caller must send `(remote_addr, link)` for each one so it enters
+    /// the book and `links`, alongside cloning `notifications_tx` into that
+    /// link's own `spawn` call so its notifications reach `notifications` too.
+    pub async fn run<N, RNG>(
+        mut self,
+        host_identity: cybershake::PrivateKey,
+        mut links: HashMap<PeerID, PeerLink>,
+        mut notifications: sync::mpsc::Receiver<PeerNotification>,
+        notifications_tx: sync::mpsc::Sender<PeerNotification>,
+        mut inbound: sync::mpsc::Receiver<(SocketAddr, PeerLink)>,
+        out: sync::mpsc::Sender<N>,
+        mut rng: RNG,
+    ) where
+        N: From<PeerNotification> + 'static,
+        RNG: RngCore + CryptoRng,
+    {
+        let mut refresh = time::interval(REFRESH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => {
+                    self.reconcile_roots(&links);
+
+                    let deficit = self.target_peer_count.saturating_sub(self.live_count());
+                    if deficit > 0 {
+                        for addr in self.pick_candidates(deficit) {
+                            if let Ok(stream) = tokio::net::TcpStream::connect(addr.addr).await {
+                                // Pin against the peer's verified root when we have
+                                // one, since a blinded peer's session id (`addr.id`)
+                                // changes every connection and would never match.
+                                let expected = addr.root.clone().unwrap_or_else(|| addr.id.clone());
+                                if let Ok(link) = PeerLink::spawn(
+                                    &host_identity,
+                                    Some(expected),
+                                    notifications_tx.clone(),
+                                    stream,
+                                    &mut rng,
+                                    None,
+                                    None,
+                                )
+                                .await
+                                {
+                                    // The negotiated session id, not the stale
+                                    // `addr.id` we dialed under: a blinded peer
+                                    // picks a fresh one every connection.
+                                    let session_id = link.id().clone();
+                                    self.mark_connected(&session_id, addr.addr, addr.root.clone());
+                                    links.insert(session_id, link);
+                                }
+                            }
+                        }
+                    }
+                    for link in links.values_mut() {
+                        link.send(PeerMessage::GetPeers).await;
+                    }
+                }
+                Some((addr, link)) = inbound.recv() => {
+                    let session_id = link.id().clone();
+                    self.mark_connected(&session_id, addr, None);
+                    links.insert(session_id, link);
+                }
+                Some(notification) = notifications.recv() => {
+                    match &notification {
+                        PeerNotification::Received(_, PeerMessage::Peers(addrs)) => {
+                            for addr in self.merge(addrs.clone()) {
+                                let _ = out.send(PeerNotification::Discovered(addr).into()).await;
+                            }
+                        }
+                        PeerNotification::Received(from, PeerMessage::GetPeers) => {
+                            if let Some(link) = links.get_mut(from) {
+                                let addrs = self.book.values().map(|e| e.addr.clone()).collect();
+                                link.send(PeerMessage::Peers(addrs)).await;
+                            }
+                        }
+                        PeerNotification::Received(from, PeerMessage::Hello(port)) => {
+                            self.record_hello(from, *port);
+                        }
+                        PeerNotification::Disconnected(id) => {
+                            self.mark_disconnected(id);
+                            links.remove(id);
+                        }
+                        _ => {}
+                    }
+                    let _ = out.send(notification.into()).await;
+                }
+                else => break,
+            }
+        }
+    }
+}
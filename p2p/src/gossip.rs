@@ -0,0 +1,133 @@
+//! Flood-gossip broadcast over `PeerManager`, deduplicated by content hash
+//! so a payload injected at one node reaches the whole mesh without looping.
+use std::collections::{HashSet, VecDeque};
+
+use sha2::{Digest, Sha256};
+
+use crate::manager::PeerManager;
+use crate::peer::{PeerID, PeerMessage, PeerNotification};
+
+/// Default number of gossip ids kept for dedup before the oldest is evicted.
+const DEFAULT_SEEN_CAPACITY: usize = 10_000;
+/// Default number of hops a locally-originated gossip message may travel.
+const DEFAULT_TTL: u8 = 8;
+
+/// Bounded FIFO set of seen gossip ids, used to drop already-delivered messages.
+struct SeenSet {
+    capacity: usize,
+    order: VecDeque<[u8; 32]>,
+    members: HashSet<[u8; 32]>,
+}
+
+impl SeenSet {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::new(),
+            members: HashSet::new(),
+        }
+    }
+
+    /// Records `id`, evicting the oldest entry if at capacity. Returns
+    /// `true` if `id` had already been seen.
+    fn check_and_insert(&mut self, id: [u8; 32]) -> bool {
+        if !self.members.insert(id) {
+            return true;
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.members.remove(&oldest);
+            }
+        }
+        false
+    }
+}
+
+/// Drives flood-gossip over a `PeerManager`: seeds local broadcasts, drops
+/// duplicates, and re-floods fresh messages to every peer but the sender.
+pub struct Gossip {
+    seen: SeenSet,
+    ttl: u8,
+}
+
+impl Gossip {
+    /// Creates a gossip layer keeping up to `seen_capacity` message ids and
+    /// flooding locally-originated messages `ttl` hops.
+    pub fn new(seen_capacity: usize, ttl: u8) -> Self {
+        Self {
+            seen: SeenSet::new(seen_capacity),
+            ttl,
+        }
+    }
+
+    fn hash(payload: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(payload);
+        hasher.finalize().into()
+    }
+
+    /// Assigns a content-hash id to `payload` and seeds the flood from this
+    /// node. Called through `PeerManager::gossip`, the entry point callers
+    /// are expected to use.
+    pub async fn seed(&mut self, manager: &mut PeerManager, payload: Vec<u8>) {
+        let id = Self::hash(&payload);
+        self.seen.check_and_insert(id);
+        manager
+            .broadcast(PeerMessage::Gossip {
+                id,
+                ttl: self.ttl,
+                payload,
+            })
+            .await;
+    }
+
+    /// Handles an inbound `PeerMessage`, re-flooding it to every peer but
+    /// `from` if it hasn't been seen before. Returns the decoded
+    /// `PeerNotification::Gossip` to deliver locally, or `None` for
+    /// non-gossip messages, duplicates, and messages whose `id` is not the
+    /// hash of their payload.
+    pub async fn handle(
+        &mut self,
+        manager: &mut PeerManager,
+        from: &PeerID,
+        msg: PeerMessage,
+    ) -> Option<PeerNotification> {
+        let (id, ttl, payload) = match msg {
+            PeerMessage::Gossip { id, ttl, payload } => (id, ttl, payload),
+            _ => return None,
+        };
+
+        // `id` is attacker-controlled: without this check a peer could seed
+        // the seen-set with an arbitrary id to suppress a genuine message
+        // with that hash network-wide before it ever arrives.
+        if id != Self::hash(&payload) {
+            return None;
+        }
+
+        if self.seen.check_and_insert(id) {
+            return None;
+        }
+
+        if ttl > 1 {
+            manager
+                .broadcast_except(
+                    from,
+                    PeerMessage::Gossip {
+                        id,
+                        ttl: ttl - 1,
+                        payload: payload.clone(),
+                    },
+                )
+                .await;
+        }
+
+        Some(PeerNotification::Gossip(payload))
+    }
+}
+
+impl Default for Gossip {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEEN_CAPACITY, DEFAULT_TTL)
+    }
+}
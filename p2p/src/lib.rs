@@ -0,0 +1,14 @@
+//! Peer-to-peer networking primitives: authenticated peer links, discovery,
+//! reconnection and the higher-level subsystems built on top of them.
+pub mod cybershake;
+pub mod peer;
+
+pub mod discovery;
+pub mod gossip;
+pub mod manager;
+pub mod supervisor;
+
+pub use peer::{
+    BlindingFactor, CustomMessage, CustomMessageHandler, PeerAddr, PeerID, PeerLink, PeerMessage,
+    PeerNotification, CUSTOM_MESSAGE_TYPE_FLOOR,
+};
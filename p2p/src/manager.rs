@@ -0,0 +1,198 @@
+//! Owns every `PeerLink` for a host, enforcing connection-slot caps and
+//! exposing a single handle through which the whole swarm is driven.
+use std::collections::HashMap;
+
+use crate::gossip::Gossip;
+use crate::peer::{PeerAddr, PeerID, PeerLink, PeerMessage};
+
+/// How a peer came to be connected, and therefore how eagerly it's kept
+/// around under slot pressure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerRelation {
+    /// User-configured peer. Never evicted to make room for others.
+    Permanent,
+    /// Learned via discovery/gossip. Evicted first under slot pressure.
+    Discovered,
+    /// Accepted an inbound dial from this peer. Evicted before `Permanent`.
+    Inbound,
+}
+
+/// Caps on how many links `PeerManager` will hold at once.
+#[derive(Clone, Copy, Debug)]
+pub struct SlotLimits {
+    pub total: usize,
+    pub inbound: usize,
+    pub outbound: usize,
+}
+
+struct ManagedPeer {
+    link: PeerLink,
+    addr: PeerAddr,
+    relation: PeerRelation,
+}
+
+/// Errors returned by `PeerManager` when admitting a new link.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdmitError {
+    /// A link to this peer already exists: same `PeerID`, or same root
+    /// identity when known.
+    AlreadyConnected,
+    /// No slot is free and no non-`Permanent` peer (of the full direction,
+    /// when a directional cap is the one reached) could be evicted to free one.
+    NoSlotAvailable,
+}
+
+/// Owns all `PeerLink`s for a host, enforcing total/inbound/outbound slot
+/// caps and tagging every peer with the relation that brought it in.
+pub struct PeerManager {
+    limits: SlotLimits,
+    peers: HashMap<PeerID, ManagedPeer>,
+}
+
+impl PeerManager {
+    pub fn new(limits: SlotLimits) -> Self {
+        Self {
+            limits,
+            peers: HashMap::new(),
+        }
+    }
+
+    fn inbound_count(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|p| p.relation == PeerRelation::Inbound)
+            .count()
+    }
+
+    fn outbound_count(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|p| p.relation != PeerRelation::Inbound)
+            .count()
+    }
+
+    /// Picks the lowest-priority peer to evict to make room, if any. When
+    /// `direction` is given, only a peer of that direction (inbound vs.
+    /// everything else) is considered, so freeing a directional slot never
+    /// evicts a peer from the other direction and leaves the cap that
+    /// actually triggered eviction still exceeded.
+    fn evict_candidate(&self, direction: Option<PeerRelation>) -> Option<PeerID> {
+        self.peers
+            .values()
+            .filter(|p| p.relation != PeerRelation::Permanent)
+            .filter(|p| match direction {
+                Some(PeerRelation::Inbound) => p.relation == PeerRelation::Inbound,
+                Some(_) => p.relation != PeerRelation::Inbound,
+                None => true,
+            })
+            .min_by_key(|p| match p.relation {
+                PeerRelation::Discovered => 0,
+                PeerRelation::Inbound => 1,
+                PeerRelation::Permanent => 2,
+            })
+            .map(|p| p.addr.id.clone())
+    }
+
+    /// Admits a newly spawned `PeerLink`, evicting the lowest-priority
+    /// non-`Permanent` peer (see `PeerRelation`) if slots are full. Refuses
+    /// duplicate connections to a peer already linked, matched by `PeerID`
+    /// or, when known, by root identity.
+    pub fn admit(
+        &mut self,
+        link: PeerLink,
+        addr: PeerAddr,
+        relation: PeerRelation,
+    ) -> Result<(), AdmitError> {
+        let root = addr.root.as_ref().unwrap_or(&addr.id);
+        let duplicate = self.peers.contains_key(&addr.id)
+            || self
+                .peers
+                .values()
+                .any(|p| p.addr.root.as_ref().unwrap_or(&p.addr.id) == root);
+        if duplicate {
+            return Err(AdmitError::AlreadyConnected);
+        }
+
+        let per_direction_full = if relation == PeerRelation::Inbound {
+            self.inbound_count() >= self.limits.inbound
+        } else {
+            self.outbound_count() >= self.limits.outbound
+        };
+
+        if per_direction_full || self.peers.len() >= self.limits.total {
+            // A directional cap must evict from that same direction, or the
+            // insert below still exceeds it even though a slot was freed.
+            let direction = if per_direction_full { Some(relation) } else { None };
+            match self.evict_candidate(direction) {
+                Some(id) => {
+                    self.peers.remove(&id);
+                }
+                None => return Err(AdmitError::NoSlotAvailable),
+            }
+        }
+
+        self.peers.insert(
+            addr.id.clone(),
+            ManagedPeer {
+                link,
+                addr,
+                relation,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes a peer, e.g. after a `PeerNotification::Disconnected`.
+    pub fn remove(&mut self, id: &PeerID) {
+        self.peers.remove(id);
+    }
+
+    /// Sends a message to every connected peer.
+    pub async fn broadcast(&mut self, msg: PeerMessage) {
+        for peer in self.peers.values_mut() {
+            peer.link.send(msg.clone()).await;
+        }
+    }
+
+    /// Sends a message to a single peer, if connected.
+    pub async fn send_to(&mut self, id: &PeerID, msg: PeerMessage) {
+        if let Some(peer) = self.peers.get_mut(id) {
+            peer.link.send(msg).await;
+        }
+    }
+
+    /// Sends a message to every connected peer other than `exclude`, e.g. to
+    /// re-flood a gossip message without sending it back to its sender.
+    pub async fn broadcast_except(&mut self, exclude: &PeerID, msg: PeerMessage) {
+        for (id, peer) in self.peers.iter_mut() {
+            if id != exclude {
+                peer.link.send(msg.clone()).await;
+            }
+        }
+    }
+
+    /// Iterates over the `PeerID`s currently connected.
+    pub fn peer_ids(&self) -> impl Iterator<Item = &PeerID> {
+        self.peers.keys()
+    }
+
+    /// Returns the relation under which `id` is tracked, if connected.
+    pub fn relation_of(&self, id: &PeerID) -> Option<PeerRelation> {
+        self.peers.get(id).map(|p| p.relation)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Seeds a flood-gossip broadcast of `payload` through `gossip`, the
+    /// canonical entry point for originating a gossip message on this
+    /// manager's peers. See `Gossip::seed`.
+    pub async fn gossip(&mut self, gossip: &mut Gossip, payload: Vec<u8>) {
+        gossip.seed(self, payload).await;
+    }
+}
@@ -5,14 +5,20 @@ use core::fmt;
 use futures::stream::StreamExt;
 use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 use tokio::io;
 use tokio::prelude::*;
 use tokio::sync;
 use tokio::task;
+use tokio::time;
 
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_TABLE;
 use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
 use rand_core::{CryptoRng, RngCore};
 
 use crate::cybershake;
@@ -21,10 +27,43 @@ use crate::cybershake;
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PeerID(cybershake::PublicKey);
 
-#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PeerAddr {
+    /// Identity observed on the wire for this session. When the peer blinds
+    /// its key (see `BlindingFactor`), this is fresh every connection.
     pub id: PeerID,
     pub addr: SocketAddr,
+    /// The peer's stable long-term identity, known only once the peer has
+    /// revealed its blinding factor for this session (see `PeerLink::revealed_root`).
+    /// Discovery and `PeerManager` dedupe on this when present, falling back to `id`.
+    pub root: Option<PeerID>,
+}
+
+/// A random per-session blinding factor used to derive an unlinkable session
+/// identity from a long-term `cybershake::PrivateKey`, so on-wire observers
+/// see a fresh `PeerID` on every connection from the same node.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct BlindingFactor(Scalar);
+
+impl BlindingFactor {
+    /// Draws a fresh random blinding factor.
+    pub fn random<RNG: RngCore + CryptoRng>(rng: &mut RNG) -> Self {
+        Self(Scalar::random(rng))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(Scalar::from_bytes_mod_order(bytes))
+    }
+}
+
+impl fmt::Debug for BlindingFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlindingFactor({})", hex::encode(self.to_bytes()))
+    }
 }
 
 /// Various kinds of messages that peers can send and receive between each other.
@@ -36,21 +75,92 @@ pub enum PeerMessage {
     Data(String),
     // A list of known peers.
     Peers(Vec<PeerAddr>),
+    // Requests the recipient to send back a `Peers` message.
+    GetPeers,
+    // Keepalive probe carrying a nonce, answered with a matching `Pong`.
+    Ping(u64),
+    // Answer to a `Ping`, carrying back the same nonce.
+    Pong(u64),
+    // Application-defined message, dispatched to a `CustomMessageHandler`
+    // registered by the host. See `CUSTOM_MESSAGE_TYPE_FLOOR`.
+    Custom { type_id: u16, payload: Vec<u8> },
+    // Flood-gossiped application payload. `id` is a content hash used for
+    // deduplication; `ttl` bounds how many more hops it may re-flood.
+    Gossip {
+        id: [u8; 32],
+        ttl: u8,
+        payload: Vec<u8>,
+    },
+    // Reveals the sender's `BlindingFactor` for this session, letting the
+    // recipient recover the sender's stable root identity via
+    // `PeerID::verified_root`. Sent automatically when the session identity
+    // was blinded; never sent on an unblinded link.
+    RootProof([u8; 32]),
 }
 
+/// `type_id`s at or above this value are reserved for applications registering
+/// a `CustomMessageHandler`; below it is reserved for future built-in
+/// `PeerMessage` variants.
+pub const CUSTOM_MESSAGE_TYPE_FLOOR: u16 = 1024;
+
+/// An application-defined message carried inside `PeerMessage::Custom`.
+pub trait CustomMessage: fmt::Debug + Send {
+    /// Allows the host to downcast back to its concrete message type.
+    fn as_any(&self) -> &dyn std::any::Any;
+}
+
+/// Lets applications multiplex their own wire messages over an authenticated
+/// `PeerLink` without editing `PeerMessage` itself, mirroring how layered p2p
+/// stacks let callers run their own protocols over one authenticated link.
+pub trait CustomMessageHandler: Send + Sync {
+    /// Decodes a payload received under `type_id`. Only called for `type_id`s
+    /// at or above `CUSTOM_MESSAGE_TYPE_FLOOR`.
+    fn read(&self, type_id: u16, payload: &[u8]) -> Result<Box<dyn CustomMessage>, cybershake::Error>;
+
+    /// Encodes an application message into its wire `type_id` and payload.
+    fn encode(&self, msg: &dyn CustomMessage) -> (u16, Vec<u8>);
+}
+
+/// How long a link may go without receiving anything before we probe it with a `Ping`.
+const IDLE_WINDOW: Duration = Duration::from_secs(15);
+/// How often we check for idleness; also the spacing between unanswered pings.
+const KEEPALIVE_TICK: Duration = Duration::from_secs(15);
+/// Pings that may go unanswered before the link is considered dead.
+const MAX_UNANSWERED_PINGS: u32 = 3;
+/// Weight given to the new sample in the RTT exponential moving average.
+const RTT_EWMA_ALPHA: f64 = 0.2;
+
 /// Interface for communication with the peer.
 pub struct PeerLink {
     peer_id: PeerID,
     channel: sync::mpsc::Sender<PeerMessage>,
+    /// Current smoothed RTT estimate, in microseconds. 0 until the first `Pong`.
+    rtt_micros: Arc<AtomicU64>,
+    custom_handler: Option<Arc<dyn CustomMessageHandler>>,
+    /// The peer's root identity, once revealed via `PeerMessage::RootProof`.
+    revealed_root: Arc<std::sync::Mutex<Option<PeerID>>>,
 }
 
 /// Notifications that we receive from the peer.
-#[derive(Clone, Debug)]
+///
+/// Deliberately not `Clone`: `Custom` carries a `Box<dyn CustomMessage>`,
+/// and `CustomMessageHandler` doesn't produce cloneable messages, so
+/// cloning this enum would mean either requiring every `CustomMessage`
+/// impl to support it or panicking/dropping the payload on that variant.
+#[derive(Debug)]
 pub enum PeerNotification {
     /// Received a message from a peer
     Received(PeerID, PeerMessage),
     /// Peer got disconnected. This message is not sent if the peer was stopped by the host.
     Disconnected(PeerID),
+    /// A new peer address was learned, e.g. via a `Peers` exchange.
+    Discovered(PeerAddr),
+    /// A disconnected outbound peer will be re-dialed after the given delay.
+    Reconnecting { id: PeerID, in_ms: u64 },
+    /// Received an application-defined message, decoded by a `CustomMessageHandler`.
+    Custom(PeerID, Box<dyn CustomMessage>),
+    /// A fresh gossip payload was delivered locally (first time seen).
+    Gossip(Vec<u8>),
 }
 
 impl PeerLink {
@@ -59,6 +169,32 @@ impl PeerLink {
         &self.peer_id
     }
 
+    /// Returns the current smoothed round-trip-time estimate, if a `Pong` has
+    /// been observed yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        match self.rtt_micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    /// Returns the peer's stable root identity, once it has revealed its
+    /// blinding factor for this session. `None` until the `RootProof` has
+    /// arrived, and always `None` on unblinded links, which never send one.
+    pub fn revealed_root(&self) -> Option<PeerID> {
+        self.revealed_root.lock().unwrap().clone()
+    }
+
+    /// Encodes and sends an application-defined message via the
+    /// `CustomMessageHandler` registered at `spawn` time. Does nothing if no
+    /// handler was registered.
+    pub async fn send_custom(&mut self, msg: &dyn CustomMessage) -> () {
+        if let Some(handler) = &self.custom_handler {
+            let (type_id, payload) = handler.encode(msg);
+            self.send(PeerMessage::Custom { type_id, payload }).await
+        }
+    }
+
     /// Sends a message to the peer.
     pub async fn send(&mut self, msg: PeerMessage) -> () {
         // We intentionally ignore the error because it's only returned if the recipient has disconnected,
@@ -77,6 +213,8 @@ impl PeerLink {
         mut notifications_channel: sync::mpsc::Sender<N>,
         socket: S,
         rng: &mut RNG,
+        custom_handler: Option<Arc<dyn CustomMessageHandler>>,
+        blinding: Option<BlindingFactor>,
     ) -> Result<Self, cybershake::Error>
     where
         S: AsyncRead + AsyncWrite + Unpin + 'static,
@@ -87,37 +225,73 @@ impl PeerLink {
         let r = io::BufReader::new(r);
         let w = io::BufWriter::new(w);
 
+        // When blinding, authenticate with a fresh per-session key derived
+        // from the long-term identity so on-wire observers see a different
+        // key every connection; the root stays recoverable via `RootProof`.
+        let session_identity = blinding.map(|factor| host_identity.blind(&factor.0));
+        let session_identity_ref = session_identity.as_ref().unwrap_or(host_identity);
+
         let (id_pubkey, mut outgoing, incoming) =
-            cybershake::cybershake(host_identity, r, w, 1000_000, rng).await?;
+            cybershake::cybershake(session_identity_ref, r, w, 1000_000, rng).await?;
 
         let id = PeerID(id_pubkey);
         let retid = id.clone();
 
-        if let Some(expected_pid) = expected_peer_id {
-            if id != expected_pid {
-                return Err(cybershake::Error::ProtocolError);
-            }
-        }
+        // A pin is checked against the de-blinded root, not the session key:
+        // a blinded peer never presents its root as `id` directly, only via
+        // a `RootProof` revealed just after connecting. If the session key
+        // already matches the pin there's nothing further to verify; if it
+        // doesn't, the first message loop below rejects immediately unless
+        // that message is the `RootProof` resolving (or refuting) the pin.
+        let mut pending_pin = match &expected_peer_id {
+            Some(expected_pid) if *expected_pid != id => Some(expected_pid.clone()),
+            _ => None,
+        };
 
-        let (cmd_sender, cmd_receiver) = sync::mpsc::channel::<PeerMessage>(100);
+        let (mut cmd_sender, cmd_receiver) = sync::mpsc::channel::<PeerMessage>(100);
+        let rtt_micros = Arc::new(AtomicU64::new(0));
+        let rtt_micros_task = rtt_micros.clone();
+        let custom_handler_task = custom_handler.clone();
+        let revealed_root = Arc::new(std::sync::Mutex::new(None));
+        let revealed_root_task = revealed_root.clone();
+
+        // Reveal our own blinding factor so whoever we connected to can
+        // recover our root identity, if they already know to expect it.
+        // Safe to reveal here: this travels over the now-authenticated,
+        // encrypted session, never in the clear on the wire.
+        if let Some(factor) = blinding {
+            let _ = cmd_sender
+                .send(PeerMessage::RootProof(factor.to_bytes()))
+                .await;
+        }
 
         enum PeerEvent {
             Send(PeerMessage),
             Receive(Result<Vec<u8>, cybershake::Error>),
+            Tick,
             Stopped,
         }
 
-        // This configures a merged stream of commands from the host and messages from the peer.
+        // This configures a merged stream of commands from the host, messages from the peer,
+        // and a keepalive timer that drives idle-timeout pings.
         let mut stream = futures::stream::select(
-            cmd_receiver
-                .map(PeerEvent::Send)
-                // when the owner drops the PeerLink, we'll get the Stopped event.
-                .chain(futures::stream::once(async { PeerEvent::Stopped })),
-            incoming.into_stream().map(PeerEvent::Receive),
+            futures::stream::select(
+                cmd_receiver
+                    .map(PeerEvent::Send)
+                    // when the owner drops the PeerLink, we'll get the Stopped event.
+                    .chain(futures::stream::once(async { PeerEvent::Stopped })),
+                incoming.into_stream().map(PeerEvent::Receive),
+            ),
+            time::interval(KEEPALIVE_TICK).map(|_| PeerEvent::Tick),
         )
         .boxed_local();
 
         task::spawn_local(async move {
+            let mut last_activity = Instant::now();
+            let mut pending_ping: Option<(u64, Instant)> = None;
+            let mut unanswered_pings: u32 = 0;
+            let connected_at = Instant::now();
+
             while let Some(event) = stream.next().await {
                 // First, handle successful events (think of this as Result::async_map)
                 let result: Result<(), Option<_>> = (async {
@@ -129,13 +303,106 @@ impl PeerLink {
                         }
                         PeerEvent::Receive(msg) => {
                             let msg = msg.map_err(Some)?;
-                            let msg = bincode::deserialize(&msg)
+                            let msg: PeerMessage = bincode::deserialize(&msg)
                                 .map_err(|_e| Some(cybershake::Error::ProtocolError))?;
 
-                            notifications_channel
-                                .send(PeerNotification::Received(id.clone(), msg).into())
-                                .await
-                                .map_err(|_| None) // stop the actor if the recipient no longer interested in notifications.
+                            last_activity = Instant::now();
+
+                            // A blinded peer sends RootProof as the very first
+                            // message after connecting, so if the pin is still
+                            // unresolved and this isn't one, the peer can never
+                            // resolve it (most likely it isn't blinded at all).
+                            // Reject now instead of delivering its messages to
+                            // the host while we can't yet tell it's the peer we
+                            // pinned.
+                            if pending_pin.is_some() && !matches!(msg, PeerMessage::RootProof(_)) {
+                                return Err(Some(cybershake::Error::ProtocolError));
+                            }
+
+                            match msg {
+                                PeerMessage::Ping(nonce) => {
+                                    let pong = PeerMessage::Pong(nonce);
+                                    let bytes = bincode::serialize(&pong)
+                                        .expect("bincode serialization should work");
+                                    outgoing.send_message(&bytes).await.map_err(Some)
+                                }
+                                PeerMessage::Pong(nonce) => {
+                                    if let Some((sent_nonce, sent_at)) = pending_ping {
+                                        if sent_nonce == nonce {
+                                            let sample = sent_at.elapsed().as_micros() as u64;
+                                            let prev = rtt_micros_task.load(Ordering::Relaxed);
+                                            let smoothed = if prev == 0 {
+                                                sample
+                                            } else {
+                                                ((1.0 - RTT_EWMA_ALPHA) * prev as f64
+                                                    + RTT_EWMA_ALPHA * sample as f64)
+                                                    as u64
+                                            };
+                                            rtt_micros_task.store(smoothed, Ordering::Relaxed);
+                                            pending_ping = None;
+                                            unanswered_pings = 0;
+                                        }
+                                    }
+                                    Ok(())
+                                }
+                                PeerMessage::RootProof(factor_bytes) => {
+                                    let factor = BlindingFactor::from_bytes(factor_bytes);
+                                    if let Some(root) = id.verified_root(&factor) {
+                                        if let Some(expected) = &pending_pin {
+                                            if root == *expected {
+                                                pending_pin = None;
+                                            } else {
+                                                // Revealed a root that isn't the one we pinned.
+                                                return Err(Some(cybershake::Error::ProtocolError));
+                                            }
+                                        }
+                                        *revealed_root_task.lock().unwrap() = Some(root);
+                                    }
+                                    Ok(())
+                                }
+                                PeerMessage::Custom { type_id, payload }
+                                    if type_id >= CUSTOM_MESSAGE_TYPE_FLOOR =>
+                                {
+                                    match &custom_handler_task {
+                                        Some(handler) => {
+                                            let decoded =
+                                                handler.read(type_id, &payload).map_err(Some)?;
+                                            notifications_channel
+                                                .send(
+                                                    PeerNotification::Custom(id.clone(), decoded)
+                                                        .into(),
+                                                )
+                                                .await
+                                                .map_err(|_| None)
+                                        }
+                                        None => Err(Some(cybershake::Error::ProtocolError)),
+                                    }
+                                }
+                                msg => notifications_channel
+                                    .send(PeerNotification::Received(id.clone(), msg).into())
+                                    .await
+                                    .map_err(|_| None), // stop the actor if the recipient no longer interested in notifications.
+                            }
+                        }
+                        PeerEvent::Tick => {
+                            if pending_pin.is_some() && connected_at.elapsed() >= IDLE_WINDOW {
+                                // Pinned peer never revealed a matching root in time.
+                                return Err(Some(cybershake::Error::ProtocolError));
+                            }
+                            if last_activity.elapsed() < IDLE_WINDOW {
+                                return Ok(());
+                            }
+                            if pending_ping.is_some() {
+                                unanswered_pings += 1;
+                                if unanswered_pings >= MAX_UNANSWERED_PINGS {
+                                    return Err(None);
+                                }
+                            }
+                            let nonce: u64 = rand::random();
+                            pending_ping = Some((nonce, Instant::now()));
+                            let bytes = bincode::serialize(&PeerMessage::Ping(nonce))
+                                .expect("bincode serialization should work");
+                            outgoing.send_message(&bytes).await.map_err(Some)
                         }
                         PeerEvent::Stopped => Err(None),
                     }
@@ -155,6 +422,9 @@ impl PeerLink {
         Ok(Self {
             peer_id: retid,
             channel: cmd_sender,
+            rtt_micros,
+            custom_handler,
+            revealed_root,
         })
     }
 }
@@ -165,6 +435,16 @@ impl PeerID {
         hex::encode(self.0.as_bytes())
     }
 
+    /// Recovers the stable long-term identity behind a blinded session
+    /// `PeerID`, given the blinding factor the peer revealed for this
+    /// session (`PeerMessage::RootProof`). Returns `None` if this `PeerID`
+    /// does not decode to a valid curve point.
+    pub fn verified_root(&self, revealed_factor: &BlindingFactor) -> Option<PeerID> {
+        let session_point = CompressedRistretto::from_slice(self.0.as_bytes()).decompress()?;
+        let root_point = session_point - &revealed_factor.0 * &RISTRETTO_BASEPOINT_TABLE;
+        Some(PeerID(cybershake::PublicKey::from(root_point.compress())))
+    }
+
     /// Decodes peer ID from string.
     pub fn from_string(id: &str) -> Option<Self> {
         hex::decode(id)
@@ -197,3 +477,25 @@ impl Hash for PeerID {
         self.0.as_bytes().hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pins the additive blinding convention `verified_root` assumes:
+    /// `session = root + factor·G`, so `session - factor·G` recovers `root`.
+    #[test]
+    fn verified_root_round_trips_additive_blinding() {
+        let mut rng = rand::thread_rng();
+
+        let root_scalar = Scalar::random(&mut rng);
+        let root_point = &root_scalar * &RISTRETTO_BASEPOINT_TABLE;
+        let root_id = PeerID(cybershake::PublicKey::from(root_point.compress()));
+
+        let factor = BlindingFactor::random(&mut rng);
+        let session_point = root_point + &factor.0 * &RISTRETTO_BASEPOINT_TABLE;
+        let session_id = PeerID(cybershake::PublicKey::from(session_point.compress()));
+
+        assert_eq!(session_id.verified_root(&factor), Some(root_id));
+    }
+}
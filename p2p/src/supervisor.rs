@@ -0,0 +1,185 @@
+//! Reconnection supervisor for outbound peers.
+//! Re-dials a peer after it disconnects, backing off exponentially between
+//! attempts, and gives up on non-pinned peers after too many failures.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use tokio::sync;
+use tokio::task;
+use tokio::time;
+
+use crate::cybershake;
+use crate::peer::{PeerID, PeerLink, PeerNotification};
+
+/// Initial delay before the first reconnection attempt.
+const BACKOFF_FLOOR: Duration = Duration::from_secs(1);
+/// The backoff never grows past this; jitter may stretch a single wait up to 20% beyond it.
+const BACKOFF_CEILING: Duration = Duration::from_secs(5 * 60);
+/// A connection that stays up at least this long resets its backoff to the floor.
+const STABLE_THRESHOLD: Duration = Duration::from_secs(30);
+/// Ephemeral (discovered) peers are dropped after this many consecutive failures.
+const EPHEMERAL_MAX_FAILURES: u32 = 8;
+
+/// Whether a supervised peer was pinned by the user (always retried) or
+/// learned via discovery (dropped after repeated failures).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permanence {
+    /// User-configured peer: retried forever.
+    Permanent,
+    /// Discovered peer: retried up to `EPHEMERAL_MAX_FAILURES` times.
+    Ephemeral,
+}
+
+struct Supervised {
+    addr: SocketAddr,
+    permanence: Permanence,
+    backoff: Duration,
+    failures: u32,
+    connected_at: Option<Instant>,
+}
+
+/// Supervises a set of outbound peers, automatically re-dialing them with
+/// exponential backoff whenever they disconnect.
+pub struct PeerSupervisor {
+    peers: HashMap<(PeerID, SocketAddr), Supervised>,
+}
+
+impl PeerSupervisor {
+    pub fn new() -> Self {
+        Self {
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Registers an outbound peer to be kept alive under supervision.
+    pub fn add(&mut self, id: PeerID, addr: SocketAddr, permanence: Permanence) {
+        self.peers.insert(
+            (id, addr),
+            Supervised {
+                addr,
+                permanence,
+                backoff: BACKOFF_FLOOR,
+                failures: 0,
+                connected_at: None,
+            },
+        );
+    }
+
+    fn jittered(delay: Duration) -> Duration {
+        let mut rng = rand::thread_rng();
+        let factor: f64 = rng.gen_range(0.8..1.2);
+        Duration::from_secs_f64(delay.as_secs_f64() * factor)
+    }
+
+    /// Call when the link for `(id, addr)` has just been established.
+    pub fn mark_connected(&mut self, id: &PeerID, addr: SocketAddr) {
+        if let Some(s) = self.peers.get_mut(&(id.clone(), addr)) {
+            s.connected_at = Some(Instant::now());
+        }
+    }
+
+    /// Call when the link for `(id, addr)` has disconnected.
+    /// Returns the delay after which the caller should re-dial, or `None` if
+    /// the peer has been given up on and should be forgotten.
+    pub fn on_disconnected(&mut self, id: &PeerID, addr: SocketAddr) -> Option<Duration> {
+        let key = (id.clone(), addr);
+        let s = self.peers.get_mut(&key)?;
+
+        let was_stable = s
+            .connected_at
+            .map(|t| t.elapsed() >= STABLE_THRESHOLD)
+            .unwrap_or(false);
+        s.connected_at = None;
+
+        let delay = if was_stable {
+            s.backoff = BACKOFF_FLOOR;
+            s.failures = 0;
+            s.backoff
+        } else {
+            s.failures += 1;
+            if s.permanence == Permanence::Ephemeral && s.failures > EPHEMERAL_MAX_FAILURES {
+                self.peers.remove(&key);
+                return None;
+            }
+            let delay = s.backoff;
+            s.backoff = (s.backoff * 2).min(BACKOFF_CEILING);
+            delay
+        };
+
+        Some(Self::jittered(delay))
+    }
+
+    /// Drives reconnection for every disconnected peer: waits out each
+    /// peer's own backoff concurrently (one peer's ceiling-length wait never
+    /// stalls another's redial), forwards `Reconnecting`/link notifications
+    /// to `notifications`, and hands each reconnected `PeerLink` back to the
+    /// caller through `links_out` so it isn't dropped (and torn down) the
+    /// moment `spawn` returns.
+    pub async fn run<N>(
+        mut self,
+        host_identity: Arc<cybershake::PrivateKey>,
+        mut disconnects: sync::mpsc::Receiver<(PeerID, SocketAddr)>,
+        notifications: sync::mpsc::Sender<N>,
+        links_out: sync::mpsc::Sender<PeerLink>,
+    ) where
+        N: From<PeerNotification> + Send + 'static,
+    {
+        let (redialed_tx, mut redialed_rx) =
+            sync::mpsc::channel::<(PeerID, SocketAddr, PeerLink)>(100);
+
+        loop {
+            tokio::select! {
+                Some((id, addr)) = disconnects.recv() => {
+                    let delay = match self.on_disconnected(&id, addr) {
+                        Some(d) => d,
+                        None => continue, // gave up on this peer
+                    };
+
+                    let _ = notifications
+                        .send(
+                            PeerNotification::Reconnecting {
+                                id: id.clone(),
+                                in_ms: delay.as_millis() as u64,
+                            }
+                            .into(),
+                        )
+                        .await;
+
+                    // Each peer's wait+redial runs on its own task so a long
+                    // backoff on one peer never blocks another's reconnect.
+                    let host_identity = host_identity.clone();
+                    let notifications = notifications.clone();
+                    let redialed_tx = redialed_tx.clone();
+                    task::spawn_local(async move {
+                        time::sleep(delay).await;
+
+                        if let Ok(stream) = tokio::net::TcpStream::connect(addr).await {
+                            let mut rng = rand_core::OsRng;
+                            if let Ok(link) = PeerLink::spawn(
+                                &host_identity,
+                                Some(id.clone()),
+                                notifications,
+                                stream,
+                                &mut rng,
+                                None,
+                                None,
+                            )
+                            .await
+                            {
+                                let _ = redialed_tx.send((id, addr, link)).await;
+                            }
+                        }
+                    });
+                }
+                Some((id, addr, link)) = redialed_rx.recv() => {
+                    self.mark_connected(&id, addr);
+                    let _ = links_out.send(link).await;
+                }
+                else => break,
+            }
+        }
+    }
+}